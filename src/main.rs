@@ -2,8 +2,11 @@
 // This program receives packets on a port and sends them back out the same port
 
 use std::ffi::CString;
-use std::os::raw::{c_char, c_int};
+use std::net::Ipv4Addr;
+use std::os::raw::{c_char, c_int, c_void};
 use std::ptr;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 // Import DPDK bindings
 use dpdk_sys::*;
@@ -13,35 +16,132 @@ const NUM_MBUFS: u32 = 1024;
 const MBUF_CACHE_SIZE: u32 = 250;
 const MAX_PKT_BURST: u16 = 32;
 
-/// Initialize a DPDK port with RX and TX queues
-unsafe fn port_init(port: u16) -> Result<(), i32> {
+// Distributor pipeline tuning: how many mbufs move through a single
+// get_pkt/return_pkt or ring enqueue/dequeue call.
+const DIST_BURST_SIZE: u32 = 8;
+const TX_RING_SIZE: u32 = 1024;
+
+const SIGINT: c_int = 2;
+const SIGTERM: c_int = 15;
+
+/// Set by `signal_handler` so the RX/worker/TX lcores can unwind and the
+/// distributor/lcores can be drained and joined before exit.
+static FORCE_QUIT: AtomicBool = AtomicBool::new(false);
+
+/// Set only after `run_distributor_pipeline`'s RX lcore has finished
+/// flushing the distributor. Distributor workers must keep polling
+/// `rte_distributor_get_pkt` until the flush completes, or packets already
+/// handed to them (but not yet returned) are never drained and are lost
+/// instead of reflected - so workers key off this flag rather than
+/// `FORCE_QUIT` directly.
+static WORKERS_QUIT: AtomicBool = AtomicBool::new(false);
+
+extern "C" {
+    fn signal(signum: c_int, handler: extern "C" fn(c_int)) -> usize;
+}
+
+extern "C" fn signal_handler(signum: c_int) {
+    if signum == SIGINT || signum == SIGTERM {
+        FORCE_QUIT.store(true, Ordering::Relaxed);
+    }
+}
+
+const SOCKET_ID_ANY: i32 = -1;
+
+/// One mbuf pool per NUMA socket present in the system, named
+/// `MBUF_POOL_s<N>` like testpmd's per-socket pools, so RX/TX queues can be
+/// set up with memory local to the NIC instead of the main lcore.
+struct NumaPools {
+    /// Parallel to `socket_ids`: `pools[i]` lives on `socket_ids[i]`.
+    pools: Vec<*mut rte_mempool>,
+    socket_ids: Vec<i32>,
+}
+
+impl NumaPools {
+    unsafe fn init() -> Result<Self, i32> {
+        let nb_sockets = rte_socket_count();
+        let mut pools = Vec::with_capacity(nb_sockets as usize);
+        let mut socket_ids = Vec::with_capacity(nb_sockets as usize);
+
+        for i in 0..nb_sockets {
+            let socket_id = rte_socket_id_by_idx(i);
+            let pool_name = CString::new(format!("MBUF_POOL_s{}", socket_id)).unwrap();
+            let pool = rte_pktmbuf_pool_create(
+                pool_name.as_ptr(),
+                NUM_MBUFS,
+                MBUF_CACHE_SIZE,
+                0,
+                RTE_MBUF_DEFAULT_BUF_SIZE as u16,
+                socket_id,
+            );
+            if pool.is_null() {
+                eprintln!("Cannot create mbuf pool for socket {}", socket_id);
+                return Err(-1);
+            }
+
+            println!("Created mbuf pool MBUF_POOL_s{} on socket {}", socket_id, socket_id);
+            pools.push(pool);
+            socket_ids.push(socket_id);
+        }
+
+        Ok(NumaPools { pools, socket_ids })
+    }
+
+    /// Return the pool resident on `port`'s socket and that socket id,
+    /// falling back to socket 0 when the device reports `SOCKET_ID_ANY`.
+    /// If no pool exists on that socket, the first pool is returned, along
+    /// with the port's socket id rather than the pool's.
+    unsafe fn pool_for_port(&self, port: u16) -> (*mut rte_mempool, i32) {
+        let mut socket_id = rte_eth_dev_socket_id(port);
+        if socket_id == SOCKET_ID_ANY {
+            socket_id = 0;
+        }
+
+        let pool = self
+            .socket_ids
+            .iter()
+            .position(|&s| s == socket_id)
+            .map(|i| self.pools[i])
+            .unwrap_or(self.pools[0]);
+
+        (pool, socket_id)
+    }
+}
+
+/// Initialize a DPDK port with RX and TX queues. When `start` is false the
+/// device is left configured but stopped, which is what a bonded port's
+/// slaves require before `rte_eth_bond_slave_add` will accept them.
+/// `flow_rules_file`, if given, is a steering/drop rule file installed via
+/// `rte_flow` once the port has started. Returns the number of RX queues
+/// the device was configured with, so callers that poll the port (e.g.
+/// `wire_ports`) know to drain queues a `-> queue N` rule may have steered
+/// traffic to, not just queue 0.
+unsafe fn port_init(
+    port: u16,
+    start: bool,
+    flow_rules_file: Option<&str>,
+    numa_pools: &NumaPools,
+) -> Result<u16, i32> {
     // Check if port is valid
     if rte_eth_dev_is_valid_port(port) == 0 {
         return Err(-1);
     }
 
-    // Create mbuf pool
-    let pool_name = CString::new(format!("MBUF_POOL_{}", port)).unwrap();
-    let mbuf_pool = rte_pktmbuf_pool_create(
-        pool_name.as_ptr(),
-        NUM_MBUFS,
-        MBUF_CACHE_SIZE,
-        0,
-        RTE_MBUF_DEFAULT_BUF_SIZE as u16,
-        rte_socket_id() as i32,
+    // Pick the mbuf pool resident on this port's NUMA socket, not whatever
+    // socket the main lcore happens to be running on.
+    let (mbuf_pool, socket_id) = numa_pools.pool_for_port(port);
+    println!(
+        "Port {} is on socket {}, using pool MBUF_POOL_s{}",
+        port, socket_id, socket_id
     );
 
-    if mbuf_pool.is_null() {
-        eprintln!("Cannot create mbuf pool");
-        return Err(-1);
-    }
-
-    // Initialize port configuration
-    let port_conf: rte_eth_conf = std::mem::zeroed();
-    let rx_rings: u16 = 1;
-    let tx_rings: u16 = 1;
-    let mut nb_rxd = RING_SIZE;
-    let mut nb_txd = RING_SIZE;
+    // Parse the flow rules up front, before configuring the device: rules
+    // that steer to "queue N" need queue N to actually be set up, not just
+    // queue 0.
+    let rules = match flow_rules_file {
+        Some(path) => Some(parse_flow_rules(path)?),
+        None => None,
+    };
 
     // Get device info
     let mut dev_info: rte_eth_dev_info = std::mem::zeroed();
@@ -51,6 +151,37 @@ unsafe fn port_init(port: u16) -> Result<(), i32> {
         return Err(retval);
     }
 
+    // Initialize port configuration
+    let port_conf: rte_eth_conf = std::mem::zeroed();
+    let rx_rings: u16 = match &rules {
+        Some(rs) => {
+            let max_queue = rs
+                .iter()
+                .filter_map(|r| match r.action {
+                    FlowAction::Queue(q) => Some(q),
+                    FlowAction::Drop => None,
+                })
+                .max()
+                .map_or(1, |q| q + 1);
+
+            if max_queue as u32 > dev_info.max_rx_queues {
+                eprintln!(
+                    "Flow rule targets queue {} but port {} only supports {} RX queue(s)",
+                    max_queue - 1,
+                    port,
+                    dev_info.max_rx_queues
+                );
+                return Err(-1);
+            }
+
+            max_queue
+        }
+        None => 1,
+    };
+    let tx_rings: u16 = 1;
+    let mut nb_rxd = RING_SIZE;
+    let mut nb_txd = RING_SIZE;
+
     // Configure the Ethernet device
     let retval = rte_eth_dev_configure(port, rx_rings, tx_rings, &port_conf);
     if retval != 0 {
@@ -65,18 +196,20 @@ unsafe fn port_init(port: u16) -> Result<(), i32> {
         return Err(retval);
     }
 
-    // Set up RX queue
-    let retval = rte_eth_rx_queue_setup(
-        port,
-        0,
-        nb_rxd,
-        rte_eth_dev_socket_id(port) as u32,
-        ptr::null(),
-        mbuf_pool,
-    );
-    if retval < 0 {
-        eprintln!("Error setting up RX queue: {}", retval);
-        return Err(retval);
+    // Set up RX queue(s), backed by the NUMA-local pool resolved above
+    for queue in 0..rx_rings {
+        let retval = rte_eth_rx_queue_setup(
+            port,
+            queue,
+            nb_rxd,
+            socket_id as u32,
+            ptr::null(),
+            mbuf_pool,
+        );
+        if retval < 0 {
+            eprintln!("Error setting up RX queue {}: {}", queue, retval);
+            return Err(retval);
+        }
     }
 
     // Set up TX queue
@@ -86,7 +219,7 @@ unsafe fn port_init(port: u16) -> Result<(), i32> {
         port,
         0,
         nb_txd,
-        rte_eth_dev_socket_id(port) as u32,
+        socket_id as u32,
         &txconf,
     );
     if retval < 0 {
@@ -94,6 +227,10 @@ unsafe fn port_init(port: u16) -> Result<(), i32> {
         return Err(retval);
     }
 
+    if !start {
+        return Ok(rx_rings);
+    }
+
     // Start the Ethernet port
     let retval = rte_eth_dev_start(port);
     if retval < 0 {
@@ -126,42 +263,658 @@ unsafe fn port_init(port: u16) -> Result<(), i32> {
         return Err(retval);
     }
 
-    Ok(())
+    if let Some(rules) = rules {
+        // The returned handles are dropped here: the rules stay installed
+        // for the life of the process, same as every other DPDK resource
+        // this example allocates and never tears down.
+        install_flow_rules(port, &rules)?;
+    }
+
+    Ok(rx_rings)
+}
+
+/// A single `rte_flow` steering/drop rule as read from a rules file.
+#[derive(Debug, Clone, Copy)]
+enum FlowAction {
+    Queue(u16),
+    Drop,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct FlowRule {
+    src_ip: Ipv4Addr,
+    dst_ip: Ipv4Addr,
+    src_port: u16,
+    dst_port: u16,
+    proto: u8,
+    action: FlowAction,
+}
+
+const IPPROTO_TCP: u8 = 6;
+const IPPROTO_UDP: u8 = 17;
+
+/// Parse a rules file, one rule per line:
+///   <src_ip> <dst_ip> <src_port> <dst_port> <tcp|udp> -> queue <N>
+///   <src_ip> <dst_ip> <src_port> <dst_port> <tcp|udp> -> drop
+/// Blank lines and lines starting with '#' are skipped.
+fn parse_flow_rules(path: &str) -> Result<Vec<FlowRule>, i32> {
+    let contents = std::fs::read_to_string(path).map_err(|e| {
+        eprintln!("Error reading flow rules file '{}': {}", path, e);
+        -1
+    })?;
+
+    let mut rules = Vec::new();
+    for (lineno, raw_line) in contents.lines().enumerate() {
+        let line = raw_line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let halves: Vec<&str> = line.splitn(2, "->").collect();
+        if halves.len() != 2 {
+            eprintln!("Malformed flow rule at line {}: {}", lineno + 1, raw_line);
+            return Err(-1);
+        }
+
+        let fields: Vec<&str> = halves[0].split_whitespace().collect();
+        if fields.len() != 5 {
+            eprintln!("Malformed flow rule at line {}: {}", lineno + 1, raw_line);
+            return Err(-1);
+        }
+
+        let bad_rule = |_| {
+            eprintln!("Malformed flow rule at line {}: {}", lineno + 1, raw_line);
+            -1
+        };
+
+        let src_ip = Ipv4Addr::from_str(fields[0]).map_err(bad_rule)?;
+        let dst_ip = Ipv4Addr::from_str(fields[1]).map_err(bad_rule)?;
+        let src_port: u16 = fields[2].parse().map_err(bad_rule)?;
+        let dst_port: u16 = fields[3].parse().map_err(bad_rule)?;
+        let proto = match fields[4].to_lowercase().as_str() {
+            "tcp" => IPPROTO_TCP,
+            "udp" => IPPROTO_UDP,
+            other => {
+                eprintln!("Unknown protocol '{}' at line {}", other, lineno + 1);
+                return Err(-1);
+            }
+        };
+
+        let action_str = halves[1].trim();
+        let action = if action_str == "drop" {
+            FlowAction::Drop
+        } else if let Some(queue_str) = action_str.strip_prefix("queue ") {
+            FlowAction::Queue(queue_str.trim().parse().map_err(bad_rule)?)
+        } else {
+            eprintln!("Unknown action '{}' at line {}", action_str, lineno + 1);
+            return Err(-1);
+        };
+
+        rules.push(FlowRule { src_ip, dst_ip, src_port, dst_port, proto, action });
+    }
+
+    Ok(rules)
+}
+
+/// Build and install one `rte_flow` rule per entry in `rules`, validating
+/// before create, and return the handles so they can be torn down later.
+unsafe fn install_flow_rules(port: u16, rules: &[FlowRule]) -> Result<Vec<*mut rte_flow>, i32> {
+    let mut handles = Vec::with_capacity(rules.len());
+
+    for rule in rules {
+        let mut attr: rte_flow_attr = std::mem::zeroed();
+        attr.set_ingress(1);
+
+        let eth_spec: rte_flow_item_eth = std::mem::zeroed();
+        let eth_mask: rte_flow_item_eth = std::mem::zeroed();
+
+        let mut ipv4_spec: rte_flow_item_ipv4 = std::mem::zeroed();
+        ipv4_spec.hdr.src_addr = u32::from(rule.src_ip).to_be();
+        ipv4_spec.hdr.dst_addr = u32::from(rule.dst_ip).to_be();
+        ipv4_spec.hdr.next_proto_id = rule.proto;
+        let mut ipv4_mask: rte_flow_item_ipv4 = std::mem::zeroed();
+        ipv4_mask.hdr.src_addr = u32::MAX;
+        ipv4_mask.hdr.dst_addr = u32::MAX;
+        ipv4_mask.hdr.next_proto_id = 0xff;
+
+        // UDP and TCP headers share the same src/dst port layout, so one
+        // spec/mask pair covers both.
+        let mut l4_spec: rte_flow_item_udp = std::mem::zeroed();
+        l4_spec.hdr.src_port = rule.src_port.to_be();
+        l4_spec.hdr.dst_port = rule.dst_port.to_be();
+        let mut l4_mask: rte_flow_item_udp = std::mem::zeroed();
+        l4_mask.hdr.src_port = 0xffff;
+        l4_mask.hdr.dst_port = 0xffff;
+
+        let l4_type = if rule.proto == IPPROTO_TCP {
+            rte_flow_item_type_RTE_FLOW_ITEM_TYPE_TCP
+        } else {
+            rte_flow_item_type_RTE_FLOW_ITEM_TYPE_UDP
+        };
+
+        let pattern = [
+            rte_flow_item {
+                type_: rte_flow_item_type_RTE_FLOW_ITEM_TYPE_ETH,
+                spec: &eth_spec as *const _ as *const c_void,
+                last: ptr::null(),
+                mask: &eth_mask as *const _ as *const c_void,
+            },
+            rte_flow_item {
+                type_: rte_flow_item_type_RTE_FLOW_ITEM_TYPE_IPV4,
+                spec: &ipv4_spec as *const _ as *const c_void,
+                last: ptr::null(),
+                mask: &ipv4_mask as *const _ as *const c_void,
+            },
+            rte_flow_item {
+                type_: l4_type,
+                spec: &l4_spec as *const _ as *const c_void,
+                last: ptr::null(),
+                mask: &l4_mask as *const _ as *const c_void,
+            },
+            rte_flow_item {
+                type_: rte_flow_item_type_RTE_FLOW_ITEM_TYPE_END,
+                spec: ptr::null(),
+                last: ptr::null(),
+                mask: ptr::null(),
+            },
+        ];
+
+        let queue_action = rte_flow_action_queue {
+            index: match rule.action {
+                FlowAction::Queue(queue) => queue,
+                FlowAction::Drop => 0,
+            },
+        };
+        let actions = match rule.action {
+            FlowAction::Queue(_) => [
+                rte_flow_action {
+                    type_: rte_flow_action_type_RTE_FLOW_ACTION_TYPE_QUEUE,
+                    conf: &queue_action as *const _ as *const c_void,
+                },
+                rte_flow_action {
+                    type_: rte_flow_action_type_RTE_FLOW_ACTION_TYPE_END,
+                    conf: ptr::null(),
+                },
+            ],
+            FlowAction::Drop => [
+                rte_flow_action {
+                    type_: rte_flow_action_type_RTE_FLOW_ACTION_TYPE_DROP,
+                    conf: ptr::null(),
+                },
+                rte_flow_action {
+                    type_: rte_flow_action_type_RTE_FLOW_ACTION_TYPE_END,
+                    conf: ptr::null(),
+                },
+            ],
+        };
+
+        let mut error: rte_flow_error = std::mem::zeroed();
+        let ret = rte_flow_validate(port, &attr, pattern.as_ptr(), actions.as_ptr(), &mut error);
+        if ret != 0 {
+            eprintln!("Flow rule validation failed for port {}: {}", port, ret);
+            return Err(ret);
+        }
+
+        let handle = rte_flow_create(port, &attr, pattern.as_ptr(), actions.as_ptr(), &mut error);
+        if handle.is_null() {
+            eprintln!("Error creating flow rule on port {}", port);
+            return Err(-1);
+        }
+
+        handles.push(handle);
+    }
+
+    println!("Installed {} flow rule(s) on port {}", handles.len(), port);
+
+    Ok(handles)
+}
+
+/// Parse a bonding mode by its DPDK-standard name.
+fn parse_bond_mode(s: &str) -> Option<u8> {
+    match s {
+        "round-robin" => Some(0),
+        "active-backup" => Some(1),
+        "balance-xor" => Some(2),
+        "broadcast" => Some(3),
+        "8023ad" | "lacp" => Some(4),
+        "tlb" => Some(5),
+        "alb" => Some(6),
+        _ => None,
+    }
+}
+
+/// Create a bonded port aggregating `slaves` under the given bonding `mode`,
+/// then configure and start it exactly like a regular port.
+unsafe fn bond_init(mode: u8, slaves: &[u16], numa_pools: &NumaPools) -> Result<u16, i32> {
+    if slaves.is_empty() {
+        eprintln!("Bonding requires at least one slave port");
+        return Err(-1);
+    }
+
+    let bond_name = CString::new("bond0").unwrap();
+    let bond_port = rte_eth_bond_create(bond_name.as_ptr(), mode, rte_socket_id() as u8);
+    if bond_port < 0 {
+        eprintln!("Error creating bonded device: {}", bond_port);
+        return Err(bond_port);
+    }
+    let bond_port = bond_port as u16;
+
+    for &slave in slaves {
+        // Slaves must be configured but left stopped before they can be
+        // attached to the bond.
+        port_init(slave, false, None, numa_pools)?;
+
+        let retval = rte_eth_bond_slave_add(bond_port, slave);
+        if retval < 0 {
+            eprintln!("Error adding slave port {} to bond0: {}", slave, retval);
+            return Err(retval);
+        }
+    }
+
+    // Configure and start the bonded device itself, same as any other port.
+    port_init(bond_port, true, None, numa_pools)?;
+
+    println!(
+        "Bonded port {} created in mode {} from slaves {:?}",
+        bond_port, mode, slaves
+    );
+
+    Ok(bond_port)
 }
 
 /// Main packet forwarding loop
-unsafe fn wire_ports(in_port: u16, out_port: u16) {
+/// Software-side forwarding counters, sampled once per reporting interval
+/// alongside the NIC's own hardware stats.
+#[derive(Default, Clone, Copy)]
+struct PortStats {
+    forwarded: u64,
+    dropped: u64,
+}
+
+/// How often the stats snapshot in `wire_ports` fires, via the TSC rather
+/// than a wall-clock syscall.
+const STATS_INTERVAL_SECS: f64 = 1.0;
+/// How many extended (xstats) counters to print per snapshot.
+const NB_XSTATS_SHOWN: usize = 5;
+
+/// Snapshot and print software counters, `rte_eth_stats_get` hardware
+/// counters, and a handful of named xstats, along with per-interval packet
+/// and bit rates. Called from the stats timer in `wire_ports`, never from
+/// the hot RX/TX path.
+unsafe fn report_port_stats(
+    port: u16,
+    stats: PortStats,
+    prev_stats: PortStats,
+    prev_hw: &rte_eth_stats,
+    interval_secs: f64,
+) -> rte_eth_stats {
+    let mut hw: rte_eth_stats = std::mem::zeroed();
+    if rte_eth_stats_get(port, &mut hw) != 0 {
+        eprintln!("Error reading hardware stats for port {}", port);
+        return hw;
+    }
+
+    let fwd_pps = (stats.forwarded - prev_stats.forwarded) as f64 / interval_secs;
+    let drop_pps = (stats.dropped - prev_stats.dropped) as f64 / interval_secs;
+    let rx_bps = (hw.ibytes - prev_hw.ibytes) as f64 * 8.0 / interval_secs;
+    let tx_bps = (hw.obytes - prev_hw.obytes) as f64 * 8.0 / interval_secs;
+
+    println!("=== Port {} stats ===", port);
+    println!(
+        "  SW forwarded: {} ({:.0} pkt/s)   SW dropped: {} ({:.0} pkt/s)",
+        stats.forwarded, fwd_pps, stats.dropped, drop_pps
+    );
+    println!(
+        "  HW ipackets: {} opackets: {} imissed: {} ierrors: {} rx_nombuf: {}",
+        hw.ipackets, hw.opackets, hw.imissed, hw.ierrors, hw.rx_nombuf
+    );
+    println!(
+        "  RX rate: {:.2} Mbit/s   TX rate: {:.2} Mbit/s",
+        rx_bps / 1_000_000.0,
+        tx_bps / 1_000_000.0
+    );
+
+    let nb_xstats = rte_eth_xstats_get_names(port, ptr::null_mut(), 0);
+    if nb_xstats > 0 {
+        let mut names: Vec<rte_eth_xstat_name> = vec![std::mem::zeroed(); nb_xstats as usize];
+        let mut values: Vec<u64> = vec![0; nb_xstats as usize];
+        rte_eth_xstats_get_names(port, names.as_mut_ptr(), nb_xstats as u32);
+        rte_eth_xstats_get(port, values.as_mut_ptr(), nb_xstats as u32);
+
+        for (name, value) in names.iter().zip(values.iter()).take(NB_XSTATS_SHOWN) {
+            let cname = std::ffi::CStr::from_ptr(name.name.as_ptr());
+            println!("  xstat {}: {}", cname.to_string_lossy(), value);
+        }
+    }
+
+    hw
+}
+
+/// Main packet forwarding loop. Stays free of I/O except for the once-a-
+/// second stats snapshot, driven by the TSC so the hot path never calls
+/// into a syscall. Polls every RX queue `in_port` was configured with
+/// (`nb_rx_queues`), not just queue 0 - a `-> queue N` flow rule steers
+/// matching traffic to queue N, and it has to be drained for that traffic
+/// to be reflected instead of sitting in the ring until it backs up.
+unsafe fn wire_ports(in_port: u16, out_port: u16, nb_rx_queues: u16) {
     let mut bufs: [*mut rte_mbuf; MAX_PKT_BURST as usize] = [ptr::null_mut(); MAX_PKT_BURST as usize];
-    let mut total_forwarded: u64 = 0;
-    let mut total_dropped: u64 = 0;
+    let mut stats = PortStats::default();
+    let mut prev_stats = PortStats::default();
+    let mut prev_hw: rte_eth_stats = std::mem::zeroed();
+
+    let hz = rte_get_timer_hz();
+    let mut next_stats_tsc = rte_get_timer_cycles() + (hz as f64 * STATS_INTERVAL_SECS) as u64;
 
     println!("Starting packet forwarding:");
-    println!("  IN:  Port {}", in_port);
+    println!("  IN:  Port {} ({} RX queue(s))", in_port, nb_rx_queues);
     println!("  OUT: Port {}", out_port);
 
     loop {
-        // Receive burst of packets
-        let nb_rx = rte_eth_rx_burst(in_port, 0, bufs.as_mut_ptr(), MAX_PKT_BURST);
+        for queue in 0..nb_rx_queues {
+            // Receive burst of packets
+            let nb_rx = rte_eth_rx_burst(in_port, queue, bufs.as_mut_ptr(), MAX_PKT_BURST);
 
-        if nb_rx > 0 {
-            // Send burst to out_port
-            let nb_tx = rte_eth_tx_burst(out_port, 0, bufs.as_mut_ptr(), nb_rx);
+            if nb_rx > 0 {
+                // Send burst to out_port
+                let nb_tx = rte_eth_tx_burst(out_port, 0, bufs.as_mut_ptr(), nb_rx);
+                stats.forwarded += nb_tx as u64;
 
-            total_forwarded += nb_tx as u64;
-            if nb_tx > 0 {
-                println!("Total forwarded packets: {}", total_forwarded);
-                println!("Total dropped packets: {}", total_dropped);
+                // Free any packets that weren't sent
+                if nb_tx < nb_rx {
+                    stats.dropped += (nb_rx - nb_tx) as u64;
+                    for i in nb_tx..nb_rx {
+                        rte_pktmbuf_free(bufs[i as usize]);
+                    }
+                }
             }
+        }
+
+        let now = rte_get_timer_cycles();
+        if now >= next_stats_tsc {
+            prev_hw = report_port_stats(out_port, stats, prev_stats, &prev_hw, STATS_INTERVAL_SECS);
+            prev_stats = stats;
+            next_stats_tsc = now + (hz as f64 * STATS_INTERVAL_SECS) as u64;
+        }
+    }
+}
 
-            // Free any packets that weren't sent
-            if nb_tx < nb_rx {
-                total_dropped += (nb_rx - nb_tx) as u64;
-                for i in nb_tx..nb_rx {
-                    rte_pktmbuf_free(bufs[i as usize]);
+/// Configure `port` with RSS across `nb_rx_queues` RX queues (and a single
+/// TX queue used by the TX lcore), using the mbuf pool resident on the
+/// port's own NUMA socket rather than wherever the main lcore happens to
+/// be running.
+unsafe fn port_init_rss(
+    port: u16,
+    nb_rx_queues: u16,
+    numa_pools: &NumaPools,
+) -> Result<(), i32> {
+    if rte_eth_dev_is_valid_port(port) == 0 {
+        return Err(-1);
+    }
+
+    let (mbuf_pool, socket_id) = numa_pools.pool_for_port(port);
+    println!(
+        "Port {} is on socket {}, using pool MBUF_POOL_s{}",
+        port, socket_id, socket_id
+    );
+
+    let mut port_conf: rte_eth_conf = std::mem::zeroed();
+    port_conf.rxmode.mq_mode = rte_eth_rx_mq_mode_ETH_MQ_RX_RSS;
+    port_conf.rx_adv_conf.rss_conf.rss_hf = (ETH_RSS_IP | ETH_RSS_UDP | ETH_RSS_TCP) as u64;
+
+    let mut nb_rxd = RING_SIZE;
+    let mut nb_txd = RING_SIZE;
+
+    let mut dev_info: rte_eth_dev_info = std::mem::zeroed();
+    let retval = rte_eth_dev_info_get(port, &mut dev_info);
+    if retval != 0 {
+        eprintln!("Error getting device info for port {}: {}", port, retval);
+        return Err(retval);
+    }
+
+    let retval = rte_eth_dev_configure(port, nb_rx_queues, 1, &port_conf);
+    if retval != 0 {
+        eprintln!("Error configuring device: {}", retval);
+        return Err(retval);
+    }
+
+    let retval = rte_eth_dev_adjust_nb_rx_tx_desc(port, &mut nb_rxd, &mut nb_txd);
+    if retval != 0 {
+        eprintln!("Error adjusting ring sizes: {}", retval);
+        return Err(retval);
+    }
+
+    for queue in 0..nb_rx_queues {
+        let retval = rte_eth_rx_queue_setup(
+            port,
+            queue,
+            nb_rxd,
+            socket_id as u32,
+            ptr::null(),
+            mbuf_pool,
+        );
+        if retval < 0 {
+            eprintln!("Error setting up RX queue {}: {}", queue, retval);
+            return Err(retval);
+        }
+    }
+
+    let mut txconf = dev_info.default_txconf;
+    txconf.offloads = port_conf.txmode.offloads;
+    let retval = rte_eth_tx_queue_setup(
+        port,
+        0,
+        nb_txd,
+        socket_id as u32,
+        &txconf,
+    );
+    if retval < 0 {
+        eprintln!("Error setting up TX queue: {}", retval);
+        return Err(retval);
+    }
+
+    let retval = rte_eth_dev_start(port);
+    if retval < 0 {
+        eprintln!("Error starting device: {}", retval);
+        return Err(retval);
+    }
+
+    rte_eth_promiscuous_enable(port);
+
+    Ok(())
+}
+
+struct WorkerArgs {
+    distributor: *mut rte_distributor,
+    worker_id: u32,
+}
+
+/// Each worker pulls its share of packets from the distributor keyed by
+/// the mbuf's RSS hash (which preserves per-flow ordering), reflects them,
+/// and hands them back so the RX lcore can see them in `returned_pkts`.
+unsafe extern "C" fn distributor_worker_main(arg: *mut c_void) -> i32 {
+    let args = Box::from_raw(arg as *mut WorkerArgs);
+    let d = args.distributor;
+    let worker_id = args.worker_id;
+
+    let mut bufs: [*mut rte_mbuf; DIST_BURST_SIZE as usize] = [ptr::null_mut(); DIST_BURST_SIZE as usize];
+    let mut nb_held: u32 = 0;
+
+    while !WORKERS_QUIT.load(Ordering::Relaxed) {
+        // `bufs` doubles as the `oldpkt` array being returned from the
+        // previous iteration, so `nb_held` must track the actual result of
+        // every call - including idle polls - or the next call resubmits
+        // packets that were already handed back, double-freeing them.
+        let nb_rx = rte_distributor_get_pkt(d, worker_id, bufs.as_mut_ptr(), bufs.as_mut_ptr(), nb_held);
+        nb_held = nb_rx.max(0) as u32;
+
+        // "Process" the packets: this reflector just reflects them, so
+        // there is nothing to mutate before handing them back.
+    }
+
+    // Drain anything still held before the worker exits.
+    if nb_held > 0 {
+        rte_distributor_return_pkt(d, worker_id, bufs.as_mut_ptr(), nb_held as i32);
+    }
+
+    0
+}
+
+struct TxArgs {
+    ring: *mut rte_ring,
+    port: u16,
+}
+
+/// Pulls reflected packets off the hand-off ring and transmits them,
+/// keeping all I/O off the RX and worker lcores.
+unsafe extern "C" fn distributor_tx_main(arg: *mut c_void) -> i32 {
+    let args = Box::from_raw(arg as *mut TxArgs);
+
+    let mut bufs: [*mut c_void; MAX_PKT_BURST as usize] = [ptr::null_mut(); MAX_PKT_BURST as usize];
+
+    while !FORCE_QUIT.load(Ordering::Relaxed) {
+        let nb_dq = rte_ring_dequeue_burst(args.ring, bufs.as_mut_ptr(), MAX_PKT_BURST as u32, ptr::null_mut());
+        if nb_dq == 0 {
+            continue;
+        }
+
+        let nb_tx = rte_eth_tx_burst(args.port, 0, bufs.as_mut_ptr() as *mut *mut rte_mbuf, nb_dq as u16);
+        for i in nb_tx..nb_dq as u16 {
+            rte_pktmbuf_free(bufs[i as usize] as *mut rte_mbuf);
+        }
+    }
+
+    0
+}
+
+/// Multi-core pipeline: an RSS-capable RX lcore feeds bursts into a
+/// `rte_distributor`, `num_workers` worker lcores reflect their share, and
+/// a dedicated TX lcore drains a hand-off ring to transmit.
+unsafe fn run_distributor_pipeline(
+    port: u16,
+    num_workers: u32,
+    numa_pools: &NumaPools,
+) -> Result<(), i32> {
+    signal(SIGINT, signal_handler);
+    signal(SIGTERM, signal_handler);
+
+    // Need one lcore per worker plus one for the TX lcore, in addition to
+    // the main lcore driving RX; otherwise rte_get_next_lcore below would
+    // hand back an already-used or invalid lcore, the launches would fail
+    // silently, and packets fed into the distributor would stall with no
+    // error.
+    let needed_lcores = num_workers + 1;
+    let available_lcores = rte_lcore_count().saturating_sub(1);
+    if available_lcores < needed_lcores {
+        eprintln!(
+            "Need {} lcore(s) for the workers and the TX lcore (in addition to the main lcore), but only {} are available; pass more with -l/-c",
+            needed_lcores, available_lcores
+        );
+        return Err(-1);
+    }
+
+    port_init_rss(port, num_workers as u16, numa_pools)?;
+
+    let dist_name = CString::new(format!("DIST_{}", port)).unwrap();
+    let distributor = rte_distributor_create(
+        dist_name.as_ptr(),
+        rte_socket_id() as u32,
+        num_workers,
+        RTE_DIST_ALG_BURST,
+    );
+    if distributor.is_null() {
+        eprintln!("Cannot create distributor");
+        return Err(-1);
+    }
+
+    let ring_name = CString::new(format!("TX_RING_{}", port)).unwrap();
+    let tx_ring = rte_ring_create(
+        ring_name.as_ptr(),
+        TX_RING_SIZE,
+        rte_socket_id() as i32,
+        0,
+    );
+    if tx_ring.is_null() {
+        eprintln!("Cannot create TX hand-off ring");
+        return Err(-1);
+    }
+
+    let mut lcore_id = rte_get_next_lcore(u32::MAX, 1, 0);
+    for worker_id in 0..num_workers {
+        let worker_args = Box::into_raw(Box::new(WorkerArgs { distributor, worker_id }));
+        let ret = rte_eal_remote_launch(Some(distributor_worker_main), worker_args as *mut c_void, lcore_id);
+        if ret != 0 {
+            eprintln!("Error launching worker {} on lcore {}: {}", worker_id, lcore_id, ret);
+            return Err(ret);
+        }
+        lcore_id = rte_get_next_lcore(lcore_id, 1, 0);
+    }
+
+    let tx_args = Box::into_raw(Box::new(TxArgs { ring: tx_ring, port }));
+    let ret = rte_eal_remote_launch(Some(distributor_tx_main), tx_args as *mut c_void, lcore_id);
+    if ret != 0 {
+        eprintln!("Error launching TX lcore on lcore {}: {}", lcore_id, ret);
+        return Err(ret);
+    }
+
+    println!(
+        "Distributor pipeline running on port {} with {} worker(s)",
+        port, num_workers
+    );
+
+    let mut bufs: [*mut rte_mbuf; MAX_PKT_BURST as usize] = [ptr::null_mut(); MAX_PKT_BURST as usize];
+    let mut returned: [*mut rte_mbuf; MAX_PKT_BURST as usize] = [ptr::null_mut(); MAX_PKT_BURST as usize];
+
+    while !FORCE_QUIT.load(Ordering::Relaxed) {
+        for queue in 0..num_workers as u16 {
+            let nb_rx = rte_eth_rx_burst(port, queue, bufs.as_mut_ptr(), MAX_PKT_BURST);
+            if nb_rx == 0 {
+                continue;
+            }
+
+            rte_distributor_process(distributor, bufs.as_mut_ptr(), nb_rx as u32);
+
+            let nb_ret = rte_distributor_returned_pkts(distributor, returned.as_mut_ptr(), MAX_PKT_BURST as u32);
+            if nb_ret > 0 {
+                let mut enqueued = 0u32;
+                while enqueued < nb_ret as u32 {
+                    enqueued += rte_ring_enqueue_burst(
+                        tx_ring,
+                        returned.as_mut_ptr().add(enqueued as usize) as *mut *mut c_void,
+                        nb_ret as u32 - enqueued,
+                        ptr::null_mut(),
+                    );
                 }
             }
         }
     }
+
+    println!("Shutting down distributor pipeline on port {}", port);
+    // Push anything still in-flight through before flushing, and only let
+    // the workers quit once the flush completes - otherwise no worker is
+    // left polling get_pkt while flush tries to drain packets already
+    // handed to them, and in-flight packets are lost instead of reflected.
+    rte_distributor_process(distributor, ptr::null_mut(), 0);
+    rte_distributor_flush(distributor);
+    WORKERS_QUIT.store(true, Ordering::Relaxed);
+    rte_eal_mp_wait_lcore();
+
+    // The TX lcore has exited: transmit what is left on the hand-off ring
+    // and whatever the flush handed back from here.
+    loop {
+        let mut nb = rte_ring_dequeue_burst(tx_ring, returned.as_mut_ptr() as *mut *mut c_void, MAX_PKT_BURST as u32, ptr::null_mut());
+        if nb == 0 {
+            nb = rte_distributor_returned_pkts(distributor, returned.as_mut_ptr(), MAX_PKT_BURST as u32).max(0) as u32;
+        }
+        if nb == 0 {
+            break;
+        }
+        let nb_tx = rte_eth_tx_burst(port, 0, returned.as_mut_ptr(), nb as u16);
+        for i in nb_tx..nb as u16 {
+            rte_pktmbuf_free(returned[i as usize]);
+        }
+    }
+
+    Ok(())
 }
 
 fn main() {
@@ -196,31 +949,206 @@ fn main() {
         let remaining_argc = (argc - ret) as usize;
 
         // Get the remaining arguments (application-specific args after --)
-        if remaining_argc != 2 {
+        if remaining_argc < 2 {
             println!("Usage: reflector [EAL options] -- <port_id>");
+            println!("   or: reflector [EAL options] -- --bond-mode <round-robin|active-backup|balance-xor|broadcast|8023ad|tlb|alb> <slave_port_id>...");
+            println!("   or: reflector [EAL options] -- --workers <num_workers> <port_id>");
             println!("Example: sudo ./reflector -l 0 --no-huge --no-pci --vdev 'net_pcap0,rx_pcap=test.pcap,tx_pcap=out.pcap' -- 0");
             std::process::exit(1);
         }
 
-        // Parse port_id from the remaining arguments
         // argv now points to the remaining arguments after EAL processing
         let remaining_argv = std::slice::from_raw_parts(argv.offset(ret as isize), remaining_argc);
-        let port_str = std::ffi::CStr::from_ptr(remaining_argv[1]).to_str().unwrap();
-        let port_id: u16 = port_str.parse().unwrap_or_else(|_| {
-            eprintln!("Invalid port number");
-            std::process::exit(1);
-        });
+        let app_args: Vec<&str> = remaining_argv[1..]
+            .iter()
+            .map(|&a| std::ffi::CStr::from_ptr(a).to_str().unwrap())
+            .collect();
 
-        // Initialize the port
-        if let Err(e) = port_init(port_id) {
-            eprintln!("Cannot init port {}: error {}", port_id, e);
-            std::process::exit(1);
+        let numa_pools = match NumaPools::init() {
+            Ok(pools) => pools,
+            Err(e) => {
+                eprintln!("Cannot set up NUMA mbuf pools: error {}", e);
+                std::process::exit(1);
+            }
+        };
+
+        if app_args[0] == "--workers" {
+            if app_args.len() != 3 {
+                eprintln!("--workers requires a worker count and a port id");
+                std::process::exit(1);
+            }
+            let num_workers: u32 = app_args[1].parse().unwrap_or_else(|_| {
+                eprintln!("Invalid worker count '{}'", app_args[1]);
+                std::process::exit(1);
+            });
+            let port_id: u16 = app_args[2].parse().unwrap_or_else(|_| {
+                eprintln!("Invalid port number");
+                std::process::exit(1);
+            });
+
+            if let Err(e) = run_distributor_pipeline(port_id, num_workers, &numa_pools) {
+                eprintln!("Distributor pipeline failed: error {}", e);
+                std::process::exit(1);
+            }
+
+            return;
         }
 
-        println!("Starting single-port loopback on port {}", port_id);
-        println!("Packets received on port {} will be sent back out port {}", port_id, port_id);
+        let (reflect_port, nb_rx_queues) = if app_args[0] == "--bond-mode" {
+            if app_args.len() < 3 {
+                eprintln!("--bond-mode requires a mode name and at least one slave port");
+                std::process::exit(1);
+            }
+            let mode = parse_bond_mode(app_args[1]).unwrap_or_else(|| {
+                eprintln!("Invalid bonding mode '{}'", app_args[1]);
+                std::process::exit(1);
+            });
+            let slaves: Vec<u16> = app_args[2..]
+                .iter()
+                .map(|s| {
+                    s.parse().unwrap_or_else(|_| {
+                        eprintln!("Invalid slave port number '{}'", s);
+                        std::process::exit(1);
+                    })
+                })
+                .collect();
+
+            match bond_init(mode, &slaves, &numa_pools) {
+                // Bonded ports are always brought up via port_init(..., None, ...),
+                // so they're always configured with a single RX queue.
+                Ok(bond_port) => (bond_port, 1),
+                Err(e) => {
+                    eprintln!("Cannot create bonded port: error {}", e);
+                    std::process::exit(1);
+                }
+            }
+        } else {
+            let port_id: u16 = app_args[0].parse().unwrap_or_else(|_| {
+                eprintln!("Invalid port number");
+                std::process::exit(1);
+            });
+
+            let flow_rules_file = if app_args.get(1) == Some(&"--flow-rules") {
+                Some(*app_args.get(2).unwrap_or_else(|| {
+                    eprintln!("--flow-rules requires a rules file path");
+                    std::process::exit(1);
+                }))
+            } else {
+                None
+            };
+
+            let nb_rx_queues = match port_init(port_id, true, flow_rules_file, &numa_pools) {
+                Ok(nb_rx_queues) => nb_rx_queues,
+                Err(e) => {
+                    eprintln!("Cannot init port {}: error {}", port_id, e);
+                    std::process::exit(1);
+                }
+            };
+
+            (port_id, nb_rx_queues)
+        };
+
+        println!("Starting single-port loopback on port {}", reflect_port);
+        println!("Packets received on port {} will be sent back out port {}", reflect_port, reflect_port);
 
         // Run loopback directly in main thread
-        wire_ports(port_id, port_id);
+        wire_ports(reflect_port, reflect_port, nb_rx_queues);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_known_bond_modes() {
+        assert_eq!(parse_bond_mode("round-robin"), Some(0));
+        assert_eq!(parse_bond_mode("active-backup"), Some(1));
+        assert_eq!(parse_bond_mode("balance-xor"), Some(2));
+        assert_eq!(parse_bond_mode("broadcast"), Some(3));
+        assert_eq!(parse_bond_mode("8023ad"), Some(4));
+        assert_eq!(parse_bond_mode("lacp"), Some(4));
+        assert_eq!(parse_bond_mode("tlb"), Some(5));
+        assert_eq!(parse_bond_mode("alb"), Some(6));
+    }
+
+    #[test]
+    fn rejects_unknown_bond_mode() {
+        assert_eq!(parse_bond_mode("bogus"), None);
+        assert_eq!(parse_bond_mode(""), None);
+        assert_eq!(parse_bond_mode("LACP"), None);
+    }
+
+    /// Writes `contents` to a uniquely-named file under the OS temp dir and
+    /// returns its path, so `parse_flow_rules` (which reads from disk) can
+    /// be exercised without needing fixture files checked into the repo.
+    fn write_rules_file(name: &str, contents: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "reflector_test_{}_{}_{}",
+            name,
+            std::process::id(),
+            contents.len()
+        ));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn parses_queue_and_drop_rules_skipping_comments_and_blanks() {
+        let path = write_rules_file(
+            "happy_path",
+            "# comment\n\
+             \n\
+             10.0.0.1 10.0.0.2 1234 80 tcp -> queue 2\n\
+             10.0.0.3 10.0.0.4 5678 53 udp -> drop\n",
+        );
+
+        let rules = parse_flow_rules(path.to_str().unwrap()).unwrap();
+        assert_eq!(rules.len(), 2);
+
+        assert_eq!(rules[0].src_ip, Ipv4Addr::new(10, 0, 0, 1));
+        assert_eq!(rules[0].dst_ip, Ipv4Addr::new(10, 0, 0, 2));
+        assert_eq!(rules[0].src_port, 1234);
+        assert_eq!(rules[0].dst_port, 80);
+        assert_eq!(rules[0].proto, IPPROTO_TCP);
+        assert!(matches!(rules[0].action, FlowAction::Queue(2)));
+
+        assert_eq!(rules[1].proto, IPPROTO_UDP);
+        assert!(matches!(rules[1].action, FlowAction::Drop));
+    }
+
+    #[test]
+    fn rejects_malformed_line() {
+        let path = write_rules_file("malformed", "10.0.0.1 10.0.0.2 1234 80 tcp\n");
+        assert!(parse_flow_rules(path.to_str().unwrap()).is_err());
+    }
+
+    #[test]
+    fn rejects_wrong_field_count() {
+        let path = write_rules_file("field_count", "10.0.0.1 10.0.0.2 1234 tcp -> drop\n");
+        assert!(parse_flow_rules(path.to_str().unwrap()).is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_protocol() {
+        let path = write_rules_file(
+            "proto",
+            "10.0.0.1 10.0.0.2 1234 80 sctp -> drop\n",
+        );
+        assert!(parse_flow_rules(path.to_str().unwrap()).is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_action() {
+        let path = write_rules_file(
+            "action",
+            "10.0.0.1 10.0.0.2 1234 80 tcp -> mirror\n",
+        );
+        assert!(parse_flow_rules(path.to_str().unwrap()).is_err());
+    }
+
+    #[test]
+    fn missing_file_is_an_error() {
+        assert!(parse_flow_rules("/nonexistent/reflector_rules.txt").is_err());
     }
 }
@@ -2,9 +2,8 @@
 // This program receives packets on a port and sends them back out the same port
 
 use std::ffi::CString;
-use std::os::raw::{c_char, c_int};
+use std::os::raw::{c_char, c_int, c_void};
 use std::ptr;
-use std::alloc::{alloc, Layout};
 use std::mem::size_of;
 
 // Import DPDK bindings
@@ -15,38 +14,103 @@ const NUM_MBUFS: u32 = 8192;
 const MBUF_CACHE_SIZE: u32 = 250;
 const MAX_PKT_BURST: u16 = 4;
 
-unsafe fn port_init(port: u16) -> Result<(), i32> {
-    if rte_eth_dev_is_valid_port(port) == 0 {
-        return Err(-1);
+// CHANGE 1: Use 2048 byte elements for the anon/xmem backends.
+// This allows 2 objects to fit perfectly in one 4KB page with NO padding.
+// It creates a standard "2K" stride which AF_XDP loves.
+// Usable data room will be: 2048 - 128 (mbuf) - 128 (headroom) = 1792 bytes.
+// This is plenty for standard MTU (1500).
+const MANUAL_ELT_SIZE: usize = 2048;
+
+const PROT_READ: c_int = 0x1;
+const PROT_WRITE: c_int = 0x2;
+const MAP_PRIVATE: c_int = 0x02;
+const MAP_ANONYMOUS: c_int = 0x20;
+const MAP_HUGETLB: c_int = 0x04_0000;
+const MAP_HUGE_SHIFT: c_int = 26;
+const HUGEPAGE_SHIFT_2MB: c_int = 21;
+
+extern "C" {
+    fn mmap(
+        addr: *mut c_void,
+        length: usize,
+        prot: c_int,
+        flags: c_int,
+        fd: c_int,
+        offset: i64,
+    ) -> *mut c_void;
+}
+
+fn mmap_failed(ptr: *mut c_void) -> bool {
+    ptr as isize == -1
+}
+
+/// How mbuf pool backing memory is obtained.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MpAllocType {
+    /// `rte_pktmbuf_pool_create`, the standard DPDK-managed pool.
+    Native,
+    /// Empty pool populated from an anonymous `mmap` region (IOVA-as-VA).
+    Anon,
+    /// Like `Anon`, but the region is additionally registered as external
+    /// memory and DMA-mapped so it is IOVA-contiguous for real NICs.
+    Xmem,
+}
+
+impl MpAllocType {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "native" => Some(MpAllocType::Native),
+            "anon" => Some(MpAllocType::Anon),
+            "xmem" => Some(MpAllocType::Xmem),
+            _ => None,
+        }
     }
+}
 
-    // --- MANUAL MEMORY ALLOCATION START ---
-    let pool_name = CString::new(format!("MBUF_POOL_{}", port)).unwrap();
-    
-    // CHANGE 1: Use 2048 byte elements.
-    // This allows 2 objects to fit perfectly in one 4KB page with NO padding.
-    // It creates a standard "2K" stride which AF_XDP loves.
-    // Usable data room will be: 2048 - 128 (mbuf) - 128 (headroom) = 1792 bytes.
-    // This is plenty for standard MTU (1500).
-    let elt_size = 2048; 
-    
-    // CHANGE 2: Calculate total memory (N * 2048)
-    let total_mem_size = (NUM_MBUFS as usize * elt_size) + 4096;
-
-    // 3. Keep Force 4KB (page) alignment for the Base Address
-    let layout = Layout::from_size_align(total_mem_size, 4096).unwrap();
-    let raw_mem = alloc(layout);
-
-    if raw_mem.is_null() {
-        eprintln!("Failed to allocate page-aligned memory");
+/// mmap an anonymous, page-aligned region to back a manually populated
+/// mempool, preferring 2MB hugepages and falling back to regular pages.
+unsafe fn mmap_pool_region(total_mem_size: usize) -> Result<*mut c_void, i32> {
+    let huge_flags = MAP_PRIVATE | MAP_ANONYMOUS | MAP_HUGETLB | (HUGEPAGE_SHIFT_2MB << MAP_HUGE_SHIFT);
+    let mut raw_mem = mmap(
+        ptr::null_mut(),
+        total_mem_size,
+        PROT_READ | PROT_WRITE,
+        huge_flags,
+        -1,
+        0,
+    );
+
+    if mmap_failed(raw_mem) {
+        // No hugepages available (or not permitted) - fall back to regular pages.
+        raw_mem = mmap(
+            ptr::null_mut(),
+            total_mem_size,
+            PROT_READ | PROT_WRITE,
+            MAP_PRIVATE | MAP_ANONYMOUS,
+            -1,
+            0,
+        );
+    }
+
+    if mmap_failed(raw_mem) {
+        eprintln!("Failed to mmap page-aligned memory for mbuf pool");
         return Err(-1);
     }
 
-    // 4. Create an EMPTY mempool
+    Ok(raw_mem)
+}
+
+/// Create an empty mempool over `raw_mem`, set it up as a ring-backed mbuf
+/// pool, and populate it with `total_mem_size` bytes at `MANUAL_ELT_SIZE` stride.
+unsafe fn populate_manual_pool(
+    pool_name: &CString,
+    raw_mem: *mut c_void,
+    total_mem_size: usize,
+) -> Result<*mut rte_mempool, i32> {
     let mbuf_pool = rte_mempool_create_empty(
         pool_name.as_ptr(),
         NUM_MBUFS,
-        elt_size as u32,
+        MANUAL_ELT_SIZE as u32,
         MBUF_CACHE_SIZE,
         size_of::<rte_pktmbuf_pool_private>() as u32,
         rte_socket_id() as i32,
@@ -58,32 +122,112 @@ unsafe fn port_init(port: u16) -> Result<(), i32> {
         return Err(-1);
     }
 
-    // 5. Set the handlers to Ring
     let ring_ops = CString::new("ring_mp_mc").unwrap();
     rte_mempool_set_ops_byname(mbuf_pool, ring_ops.as_ptr(), ptr::null_mut());
 
-    // 6. Populate the pool
-    // DPDK sees 2048 fits twice into 4096. It will pack them tightly.
     let ret = rte_mempool_populate_virt(
         mbuf_pool,
         raw_mem as *mut _,
         total_mem_size,
-        4096, 
-        None, 
+        4096,
+        None,
         ptr::null_mut(),
     );
-
     if ret < 0 {
         eprintln!("Error populating mempool: {}", ret);
         return Err(ret);
     }
 
-    // 7. Initialize the mbuf headers
     rte_pktmbuf_pool_init(mbuf_pool, ptr::null_mut());
     rte_mempool_obj_iter(mbuf_pool, Some(rte_pktmbuf_init), ptr::null_mut());
-    // --- MANUAL MEMORY ALLOCATION END ---
 
-    // The rest is standard configuration...
+    Ok(mbuf_pool)
+}
+
+/// Build the mbuf pool for `port` using the requested allocation backend.
+///
+/// - `Native` just calls `rte_pktmbuf_pool_create`.
+/// - `Anon` mmaps an anonymous (optionally hugepage-backed) region and
+///   populates an empty pool from it, IOVA-as-VA.
+/// - `Xmem` does the same, then registers the region with
+///   `rte_extmem_register` and DMA-maps it for `port` so the pool is
+///   IOVA-contiguous for real NICs.
+unsafe fn create_mbuf_pool(port: u16, alloc_type: MpAllocType) -> Result<*mut rte_mempool, i32> {
+    let pool_name = CString::new(format!("MBUF_POOL_{}", port)).unwrap();
+
+    match alloc_type {
+        MpAllocType::Native => {
+            let mbuf_pool = rte_pktmbuf_pool_create(
+                pool_name.as_ptr(),
+                NUM_MBUFS,
+                MBUF_CACHE_SIZE,
+                0,
+                RTE_MBUF_DEFAULT_BUF_SIZE as u16,
+                rte_socket_id() as i32,
+            );
+
+            if mbuf_pool.is_null() {
+                eprintln!("Cannot create mbuf pool");
+                return Err(-1);
+            }
+
+            Ok(mbuf_pool)
+        }
+
+        MpAllocType::Anon => {
+            let total_mem_size = (NUM_MBUFS as usize * MANUAL_ELT_SIZE) + 4096;
+            let raw_mem = mmap_pool_region(total_mem_size)?;
+            populate_manual_pool(&pool_name, raw_mem, total_mem_size)
+        }
+
+        MpAllocType::Xmem => {
+            let total_mem_size = (NUM_MBUFS as usize * MANUAL_ELT_SIZE) + 4096;
+            let raw_mem = mmap_pool_region(total_mem_size)?;
+
+            let ret = rte_extmem_register(
+                raw_mem as *mut _,
+                total_mem_size as u64,
+                ptr::null_mut(),
+                0,
+                4096,
+            );
+            if ret < 0 {
+                eprintln!("Error registering external memory: {}", ret);
+                return Err(ret);
+            }
+
+            let mut dev_info: rte_eth_dev_info = std::mem::zeroed();
+            let retval = rte_eth_dev_info_get(port, &mut dev_info);
+            if retval != 0 {
+                eprintln!("Error getting device info for port {}: {}", port, retval);
+                return Err(retval);
+            }
+
+            // IOVA-as-VA: the device sees the same address we just mapped.
+            let ret = rte_dev_dma_map(
+                dev_info.device,
+                raw_mem as *mut _,
+                raw_mem as u64,
+                total_mem_size as u64,
+            );
+            if ret < 0 {
+                eprintln!("Error DMA-mapping memory for port {}: {}", port, ret);
+                return Err(ret);
+            }
+
+            populate_manual_pool(&pool_name, raw_mem, total_mem_size)
+        }
+    }
+}
+
+/// Initialize a DPDK port with RX and TX queues
+unsafe fn port_init(port: u16, alloc_type: MpAllocType) -> Result<(), i32> {
+    if rte_eth_dev_is_valid_port(port) == 0 {
+        return Err(-1);
+    }
+
+    let mbuf_pool = create_mbuf_pool(port, alloc_type)?;
+
     let port_conf: rte_eth_conf = std::mem::zeroed();
     let rx_rings: u16 = 1;
     let tx_rings: u16 = 1;
@@ -139,7 +283,7 @@ unsafe fn port_init(port: u16) -> Result<(), i32> {
 
     let mut addr: rte_ether_addr = std::mem::zeroed();
     rte_eth_macaddr_get(port, &mut addr);
-    
+
     println!("Port {} MAC: {:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
         port, addr.addr_bytes[0], addr.addr_bytes[1], addr.addr_bytes[2],
         addr.addr_bytes[3], addr.addr_bytes[4], addr.addr_bytes[5]);
@@ -186,6 +330,44 @@ unsafe fn wire_ports(in_port: u16, out_port: u16) {
     }
 }
 
+/// Parse the app-specific arguments that remain after EAL options have been
+/// stripped: an optional `--mp-alloc native|anon|xmem` pair, plus the
+/// positional port id.
+fn parse_app_args(args: &[*mut c_char]) -> (MpAllocType, u16) {
+    let mut alloc_type = MpAllocType::Native;
+    let mut port_id: Option<u16> = None;
+
+    let mut i = 0;
+    while i < args.len() {
+        let arg = unsafe { std::ffi::CStr::from_ptr(args[i]) }.to_str().unwrap();
+        if arg == "--mp-alloc" {
+            i += 1;
+            if i >= args.len() {
+                eprintln!("--mp-alloc requires a value (native|anon|xmem)");
+                std::process::exit(1);
+            }
+            let value = unsafe { std::ffi::CStr::from_ptr(args[i]) }.to_str().unwrap();
+            alloc_type = MpAllocType::parse(value).unwrap_or_else(|| {
+                eprintln!("Invalid --mp-alloc value '{}' (expected native|anon|xmem)", value);
+                std::process::exit(1);
+            });
+        } else {
+            port_id = Some(arg.parse().unwrap_or_else(|_| {
+                eprintln!("Invalid port number");
+                std::process::exit(1);
+            }));
+        }
+        i += 1;
+    }
+
+    let port_id = port_id.unwrap_or_else(|| {
+        println!("Usage: reflector [EAL options] -- [--mp-alloc native|anon|xmem] <port_id>");
+        std::process::exit(1);
+    });
+
+    (alloc_type, port_id)
+}
+
 fn main() {
     unsafe {
         // Collect command line arguments as CStrings
@@ -218,23 +400,18 @@ fn main() {
         let remaining_argc = (argc - ret) as usize;
 
         // Get the remaining arguments (application-specific args after --)
-        if remaining_argc != 2 {
-            println!("Usage: reflector [EAL options] -- <port_id>");
+        if remaining_argc < 2 {
+            println!("Usage: reflector [EAL options] -- [--mp-alloc native|anon|xmem] <port_id>");
             println!("Example: sudo ./reflector -l 0 --no-huge --no-pci --vdev 'net_pcap0,rx_pcap=test.pcap,tx_pcap=out.pcap' -- 0");
             std::process::exit(1);
         }
 
-        // Parse port_id from the remaining arguments
-        // argv now points to the remaining arguments after EAL processing
+        // Parse app args from the remaining arguments after EAL processing
         let remaining_argv = std::slice::from_raw_parts(argv.offset(ret as isize), remaining_argc);
-        let port_str = std::ffi::CStr::from_ptr(remaining_argv[1]).to_str().unwrap();
-        let port_id: u16 = port_str.parse().unwrap_or_else(|_| {
-            eprintln!("Invalid port number");
-            std::process::exit(1);
-        });
+        let (alloc_type, port_id) = parse_app_args(&remaining_argv[1..]);
 
         // Initialize the port
-        if let Err(e) = port_init(port_id) {
+        if let Err(e) = port_init(port_id, alloc_type) {
             eprintln!("Cannot init port {}: error {}", port_id, e);
             std::process::exit(1);
         }
@@ -246,3 +423,22 @@ fn main() {
         wire_ports(port_id, port_id);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_known_alloc_types() {
+        assert_eq!(MpAllocType::parse("native"), Some(MpAllocType::Native));
+        assert_eq!(MpAllocType::parse("anon"), Some(MpAllocType::Anon));
+        assert_eq!(MpAllocType::parse("xmem"), Some(MpAllocType::Xmem));
+    }
+
+    #[test]
+    fn rejects_unknown_alloc_type() {
+        assert_eq!(MpAllocType::parse("bogus"), None);
+        assert_eq!(MpAllocType::parse(""), None);
+        assert_eq!(MpAllocType::parse("Native"), None);
+    }
+}